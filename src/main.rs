@@ -1,6 +1,7 @@
 use clang::{Clang, Entity, EntityKind, Type};
 use lazy_static::lazy_static;
-use std::collections::HashSet;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 use std::fmt::format;
 use std::fs::File;
 use std::io::Write;
@@ -10,6 +11,132 @@ use std::sync::Mutex;
 lazy_static! {
     static ref STRUCT_NAMES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
     static ref ENUM_NAMES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    static ref UNION_NAMES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+// 生成选项
+#[derive(Default)]
+struct Options {
+    // 动态加载模式：不生成 `foreign func`，而是运行时 dlopen/dlsym
+    dynamic_loading: bool,
+    // 动态加载时允许缺失符号（对应字段保持为空）
+    allow_missing_symbols: bool,
+    // 为头文件中的 static inline 函数生成 C 包装，使其可被调用
+    wrap_static_fns: bool,
+    // 允许列表/屏蔽列表（正则，匹配实体名）。任一允许列表非空时，
+    // 仅匹配的顶层实体会被导出，其类型依赖按需传递性引入。
+    allowlist_function: Vec<Regex>,
+    allowlist_type: Vec<Regex>,
+    allowlist_var: Vec<Regex>,
+    blocklist_function: Vec<Regex>,
+    blocklist_type: Vec<Regex>,
+    blocklist_var: Vec<Regex>,
+}
+
+impl Options {
+    // 是否启用了过滤（任一允许列表非空）
+    fn filtering_active(&self) -> bool {
+        !self.allowlist_function.is_empty()
+            || !self.allowlist_type.is_empty()
+            || !self.allowlist_var.is_empty()
+    }
+}
+
+fn matches_any(name: &str, regexes: &[Regex]) -> bool {
+    regexes.iter().any(|r| r.is_match(name))
+}
+
+// 判断某顶层实体是否为“根”：被屏蔽则否；该类允许列表非空时须匹配；
+// 否则仅在全局未启用过滤时才作为根。
+fn is_root(name: &str, allow: &[Regex], block: &[Regex], filtering_active: bool) -> bool {
+    if matches_any(name, block) {
+        return false;
+    }
+    if !allow.is_empty() {
+        return matches_any(name, allow);
+    }
+    !filtering_active
+}
+
+// 递归收集一个类型引用到的具名声明（struct/union/enum/typedef）
+fn collect_deps(ty: Type, deps: &mut HashSet<String>) {
+    match ty.get_kind() {
+        clang::TypeKind::Pointer => {
+            if let Some(pointee) = ty.get_pointee_type() {
+                collect_deps(pointee, deps);
+            }
+        }
+        clang::TypeKind::Elaborated => {
+            if let Some(inner) = ty.get_elaborated_type() {
+                collect_deps(inner, deps);
+            }
+        }
+        clang::TypeKind::ConstantArray => {
+            if let Some(element) = ty.get_element_type() {
+                collect_deps(element, deps);
+            }
+        }
+        clang::TypeKind::Record | clang::TypeKind::Enum => {
+            if let Some(decl) = ty.get_declaration() {
+                if let Some(n) = decl.get_name() {
+                    deps.insert(n);
+                }
+            }
+        }
+        clang::TypeKind::Typedef => {
+            if let Some(decl) = ty.get_declaration() {
+                if let Some(n) = decl.get_name() {
+                    deps.insert(n);
+                }
+            }
+            // 解析 typedef 背后的底层类型，以便一并引入
+            collect_deps(ty.get_canonical_type(), deps);
+        }
+        clang::TypeKind::FunctionPrototype => {
+            if let Some(ret) = ty.get_result_type() {
+                collect_deps(ret, deps);
+            }
+            if let Some(args) = ty.get_argument_types() {
+                for arg in args {
+                    collect_deps(arg, deps);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// 收集某个类型声明自身的直接依赖（字段类型 / typedef 底层）
+fn decl_deps(entity: &Entity, deps: &mut HashSet<String>) {
+    match entity.get_kind() {
+        EntityKind::StructDecl | EntityKind::UnionDecl => {
+            for field in entity.get_children() {
+                if field.get_kind() == EntityKind::FieldDecl {
+                    if let Some(ty) = field.get_type() {
+                        collect_deps(ty, deps);
+                    }
+                }
+            }
+        }
+        EntityKind::TypedefDecl => {
+            if let Some(ty) = entity.get_typedef_underlying_type() {
+                collect_deps(ty, deps);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn process_var(entity: Entity, output: &mut dyn Write) -> std::io::Result<()> {
+    let name = entity.get_name().unwrap();
+    let ty = translate_type(entity.get_type().unwrap());
+
+    if let Some(comment) = entity.get_comment() {
+        writeln!(output, "{}", comment)?;
+    }
+    writeln!(output, "foreign var {}: {}", name, ty)?;
+    writeln!(output)?;
+    Ok(())
 }
 
 fn translate_type(ty: Type) -> String {
@@ -76,7 +203,7 @@ fn translate_type(ty: Type) -> String {
 }
 
 
-fn process_enum(entity: Entity, output: &mut File) -> std::io::Result<()> {
+fn process_enum(entity: Entity, output: &mut dyn Write) -> std::io::Result<()> {
     // 处理注释
     if let Some(comment) = entity.get_comment() {
         writeln!(output, "{}", comment)?;
@@ -100,29 +227,227 @@ fn process_enum(entity: Entity, output: &mut File) -> std::io::Result<()> {
     Ok(())
 }
 
+// 位域的实际算术载体：由规范类型决定，而非字面解析显示名。
+// 返回 (数值载体类型, 是否有符号, 载体位宽)。Bool 用 UInt8 承载，
+// enum 统一按 Int32 承载（process_enum 将枚举生成为 `type X = Int32`）。
+fn bitfield_carrier(ty: Type) -> (String, bool, usize) {
+    let canon = ty.get_canonical_type();
+    match canon.get_kind() {
+        clang::TypeKind::Bool => ("UInt8".to_string(), false, 8),
+        clang::TypeKind::Enum => ("Int32".to_string(), true, 32),
+        _ => {
+            let carrier = translate_type(canon);
+            let signed = carrier.starts_with("Int");
+            let bits: usize = carrier
+                .trim_start_matches("UInt")
+                .trim_start_matches("Int")
+                .parse()
+                .unwrap_or(0);
+            (carrier, signed, bits)
+        }
+    }
+}
+
 fn process_bitfields(
     bitfields: &mut Vec<(Entity, usize)>,
-    output: &mut File,
-) -> std::io::Result<()> {
-    let total_bits: usize = bitfields.iter().map(|(_, width)| *width as usize).sum();
-    if total_bits % 8 != 0 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "位域总位数不是8的倍数",
-        ));
-    }
+    unit_index: usize,
+    output: &mut dyn Write,
+) -> std::io::Result<usize> {
+    // 一组连续的位域共用一个分配单元，按 ceil(总位数/8) 向上取整分配字节
+    let total_bits: usize = bitfields.iter().map(|(_, width)| *width).sum();
+    let unit_bytes = (total_bits + 7) / 8;
+    let unit_name = format!("bitfield_unit_{}", unit_index);
+
     writeln!(output, "    // 位域")?;
+    writeln!(
+        output,
+        "    var {}: VArray<UInt8, ${}> = VArray<UInt8, ${}>(repeat: 0)",
+        unit_name, unit_bytes, unit_bytes
+    )?;
+
+    // 记录每个位域在单元内的起始偏移（前序宽度的累加）
+    let mut bit_offset: usize = 0;
     for (field, width) in bitfields.drain(..) {
-        let field_name = field.get_name().unwrap_or("unnamed".to_string());
-        let field_type = field.get_type().unwrap().get_display_name();
-        writeln!(output, "    // {} {} : {}", field_name, field_type, width)?;
+        let start = bit_offset;
+        bit_offset += width;
+
+        // 匿名位域仅占位，不生成访问器
+        let field_name = match field.get_name() {
+            Some(n) => n,
+            None => continue,
+        };
+        let field_type = translate_type(field.get_type().unwrap());
+        // 符号性/位宽取自规范类型的整数载体，避免对 Bool/enum 等非数值显示名做算术
+        let (carrier, signed, type_bits) = bitfield_carrier(field.get_type().unwrap());
+        let is_bool = field.get_type().unwrap().get_canonical_type().get_kind()
+            == clang::TypeKind::Bool;
+
+        writeln!(output, "    // {}: {} : {}", field_name, field_type, width)?;
+        writeln!(output, "    mut prop {}: {} {{", field_name, field_type)?;
+
+        // getter：按小端逐位读入数值载体后按需符号扩展（暂不支持大端目标）
+        writeln!(output, "        get() {{")?;
+        writeln!(output, "            var result: {} = 0", carrier)?;
+        writeln!(output, "            for (i in 0..{}) {{", width)?;
+        writeln!(output, "                let bitOffset = {} + i", start)?;
+        writeln!(output, "                let byteIndex = bitOffset / 8")?;
+        writeln!(output, "                let bitInByte = bitOffset % 8")?;
+        writeln!(
+            output,
+            "                let bit = {}((this.{}[byteIndex] >> UInt8(bitInByte)) & 1)",
+            carrier, unit_name
+        )?;
+        writeln!(output, "                result |= bit << UInt8(i)")?;
+        writeln!(output, "            }}")?;
+        // 宽度等于类型位宽时无需（且不能）符号扩展，否则会整位移位导致未定义行为
+        if signed && width < type_bits {
+            // 最高位为 1 时做符号扩展
+            writeln!(
+                output,
+                "            if ((result >> UInt8({})) & 1) != 0 {{",
+                width - 1
+            )?;
+            writeln!(
+                output,
+                "                result |= ~(({} << UInt8({})) - 1)",
+                format!("{}(1)", carrier),
+                width
+            )?;
+            writeln!(output, "            }}")?;
+        }
+        // 将数值载体转换回声明类型：Bool 比较非零，其余直接返回（enum 为 Int32 别名）
+        if is_bool {
+            writeln!(output, "            return result != 0")?;
+        } else {
+            writeln!(output, "            return result")?;
+        }
+        writeln!(output, "        }}")?;
+
+        // setter：逐位清零目标位后写回源位
+        writeln!(output, "        set(value) {{")?;
+        // 先把声明类型折算为数值载体
+        if is_bool {
+            writeln!(
+                output,
+                "            let src: {} = if (value) {{ 1 }} else {{ 0 }}",
+                carrier
+            )?;
+        } else {
+            writeln!(output, "            let src: {} = {}(value)", carrier, carrier)?;
+        }
+        writeln!(output, "            for (i in 0..{}) {{", width)?;
+        writeln!(output, "                let bitOffset = {} + i", start)?;
+        writeln!(output, "                let byteIndex = bitOffset / 8")?;
+        writeln!(output, "                let bitInByte = bitOffset % 8")?;
+        writeln!(
+            output,
+            "                let bit = UInt8((src >> UInt8(i)) & 1)"
+        )?;
+        writeln!(
+            output,
+            "                this.{}[byteIndex] = (this.{}[byteIndex] & ~(1u8 << UInt8(bitInByte))) | (bit << UInt8(bitInByte))",
+            unit_name, unit_name
+        )?;
+        writeln!(output, "            }}")?;
+        writeln!(output, "        }}")?;
+        writeln!(output, "    }}")?;
+    }
+    Ok(unit_bytes)
+}
+
+// 返回字段在其所属记录内的字节偏移
+fn field_byte_offset(field: &Entity) -> Option<usize> {
+    field.get_offset_of_field().ok().map(|bits| bits / 8)
+}
+
+// 在 `current` 与 `target` 之间插入显式填充字段，返回新的偏移
+fn emit_padding(
+    current: usize,
+    target: usize,
+    pad_index: &mut usize,
+    output: &mut dyn Write,
+) -> std::io::Result<usize> {
+    if target > current {
+        let gap = target - current;
+        writeln!(
+            output,
+            "    var _padding_{}: VArray<UInt8, ${}> = VArray<UInt8, ${}>(repeat: 0)",
+            pad_index, gap, gap
+        )?;
+        *pad_index += 1;
+        Ok(target)
+    } else {
+        Ok(current)
     }
+}
+
+// 选择一个对齐恰为 `align` 的标量类型（仅 2/4/8；其余对齐返回 UInt8）
+fn align_elem_type(align: usize) -> &'static str {
+    match align {
+        8 => "Int64",
+        4 => "Int32",
+        2 => "Int16",
+        _ => "UInt8",
+    }
+}
+
+// 用“对齐宽度的元素类型 × N”表示一段 `bytes` 字节：既精确匹配大小，又让 @C 记录
+// 经由元素类型获得 `align` 对齐——无需依赖未经验证的零长度数组。C 记录的 sizeof
+// 恒为 alignof 的整数倍，故 align 为 2/4/8 且能整除时用对齐元素，否则退回逐字节
+// （此时对齐为 1，由 layout_check 报出）。
+fn aligned_layout(bytes: usize, align: usize) -> (&'static str, usize) {
+    if matches!(align, 2 | 4 | 8) && bytes % align == 0 {
+        (align_elem_type(align), bytes / align)
+    } else {
+        ("UInt8", bytes)
+    }
+}
+
+// 生成运行时布局校验函数：大小/对齐与 clang 报告不一致则抛异常。
+// 用普通 if 而非 `@Assert`（后者是 std.unittest 宏，仅能用于 @Test 且需额外导入）。
+fn emit_layout_check(
+    name: &str,
+    size: usize,
+    align: usize,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    writeln!(output, "func layout_check_{}(): Unit {{", name)?;
+    writeln!(output, "    if (sizeOf<{}>() != {}) {{", name, size)?;
     writeln!(
         output,
-        "    var bitfields: VArray<UInt8, ${}> = VArray<UInt8, ${}>(repeat: 0)",
-        total_bits / 8,
-        total_bits / 8
+        "        throw Exception(\"{} 大小不匹配: 期望 {}\")",
+        name, size
     )?;
+    writeln!(output, "    }}")?;
+    writeln!(output, "    if (alignOf<{}>() != {}) {{", name, align)?;
+    writeln!(
+        output,
+        "        throw Exception(\"{} 对齐不匹配: 期望 {}\")",
+        name, align
+    )?;
+    writeln!(output, "    }}")?;
+    writeln!(output, "}}")?;
+    writeln!(output)?;
+    Ok(())
+}
+
+// 生成聚合校验函数，逐个调用各类型的 layout_check_*，
+// 使布局断言真正可达（否则它们只是无人调用的死代码）。
+fn emit_layout_check_all(names: &[String], output: &mut dyn Write) -> std::io::Result<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    writeln!(output, "// 汇总所有类型的布局校验")?;
+    writeln!(output, "func layout_check_all(): Unit {{")?;
+    for name in names {
+        writeln!(output, "    layout_check_{}()", name)?;
+    }
+    writeln!(output, "}}")?;
+    writeln!(output)?;
+    // 包级变量初始化器在加载时运行，使 ABI 漂移立即抛异常而非静默通过
+    writeln!(output, "// 包加载时自动执行布局校验：任何 ABI 漂移都会在此抛异常")?;
+    writeln!(output, "let _layout_checked: Unit = layout_check_all()")?;
+    writeln!(output)?;
     Ok(())
 }
 
@@ -154,7 +479,7 @@ fn get_default(ty: String) -> String {
     }
 }
 
-fn process_struct(entity: Entity, output: &mut File) -> std::io::Result<()> {
+fn process_struct(entity: Entity, output: &mut dyn Write) -> std::io::Result<()> {
     let name = entity.get_name().unwrap();
 
     // 处理注释
@@ -168,18 +493,43 @@ fn process_struct(entity: Entity, output: &mut File) -> std::io::Result<()> {
         names.insert(name.clone());
     }
 
+    // 记录期望的 ABI 布局，以便生成填充并产出布局校验
+    let record_type = entity.get_type().unwrap();
+    let struct_size = record_type.get_sizeof().unwrap_or(0);
+    let struct_align = record_type.get_alignof().unwrap_or(0);
+
     writeln!(output, "@C")?;
     writeln!(output, "struct {} {{", name)?;
 
+    // @C 结构体的对齐取其对齐最大的成员，而这些成员均以同等对齐的 Cangjie 类型翻译，
+    // 故对齐自然与 C 一致；末尾的 layout_check 会断言，出现漂移即在加载时抛异常。
+
     let mut bitfields: Vec<(Entity<'_>, usize)> = Vec::new();
+    let mut bitfield_unit: usize = 0;
+    // 当前已布局到的字节偏移，用于插入显式填充
+    let mut running_offset: usize = 0;
+    let mut pad_index: usize = 0;
 
     for field in entity.get_children() {
         if field.get_kind() == EntityKind::FieldDecl {
             if let Some(bit_width) = field.get_bit_field_width() {
+                if bitfields.is_empty() {
+                    // 位域单元起始处补齐填充
+                    if let Some(off) = field_byte_offset(&field) {
+                        running_offset = emit_padding(running_offset, off, &mut pad_index, output)?;
+                    }
+                }
                 bitfields.push((field, bit_width));
                 continue;
             } else if !bitfields.is_empty() {
-                process_bitfields(&mut bitfields, output)?;
+                let bytes = process_bitfields(&mut bitfields, bitfield_unit, output)?;
+                bitfield_unit += 1;
+                running_offset += bytes;
+            }
+
+            // 普通字段：补齐到 clang 报告的实际偏移
+            if let Some(off) = field_byte_offset(&field) {
+                running_offset = emit_padding(running_offset, off, &mut pad_index, output)?;
             }
 
             let field_name = field.get_name().unwrap();
@@ -197,17 +547,26 @@ fn process_struct(entity: Entity, output: &mut File) -> std::io::Result<()> {
                 field_type,
                 get_default(field_type.clone())
             )?;
+
+            running_offset += field.get_type().unwrap().get_sizeof().unwrap_or(0);
         }
     }
 
     // 处理结尾的位域
     if !bitfields.is_empty() {
-        process_bitfields(&mut bitfields, output)?;
+        let bytes = process_bitfields(&mut bitfields, bitfield_unit, output)?;
+        running_offset += bytes;
     }
 
+    // 尾部填充到完整结构体大小
+    emit_padding(running_offset, struct_size, &mut pad_index, output)?;
+
     writeln!(output, "}}")?;
     writeln!(output)?;
 
+    // 生成布局校验：若生成类型的大小/对齐与 clang 报告的不一致即报错
+    emit_layout_check(&name, struct_size, struct_align, output)?;
+
     {
         let mut names = STRUCT_NAMES.lock().unwrap();
         names.insert(name.clone());
@@ -215,7 +574,105 @@ fn process_struct(entity: Entity, output: &mut File) -> std::io::Result<()> {
     Ok(())
 }
 
-fn process_function(entity: Entity, output: &mut File) -> std::io::Result<()> {
+fn process_union(entity: Entity, output: &mut dyn Write) -> std::io::Result<()> {
+    let name = entity.get_name().unwrap();
+
+    // 处理注释
+    if let Some(comment) = entity.get_comment() {
+        writeln!(output, "{}", comment)?;
+    }
+
+    {
+        let mut names = UNION_NAMES.lock().unwrap();
+        names.insert(name.clone());
+    }
+
+    // union 的所有成员共用同一块存储，大小取整个 union 的 sizeof
+    let union_type = entity.get_type().unwrap();
+    let union_size = union_type.get_sizeof().unwrap_or(0);
+    // union 的对齐等于其对齐最大的成员，字节数组本身对齐为 1，需强制补齐
+    let union_align = union_type.get_alignof().unwrap_or(0);
+
+    // 存储用“对齐宽度元素 × N”表示：精确匹配 sizeof，对齐为 2/4/8 时让 @C union 获得正确对齐
+    let (elem, count) = aligned_layout(union_size, union_align);
+
+    writeln!(output, "@C")?;
+    writeln!(output, "struct {} {{", name)?;
+    writeln!(
+        output,
+        "    var storage: VArray<{}, ${}> = VArray<{}, ${}>(repeat: 0)",
+        elem, count, elem, count
+    )?;
+
+    // 每个成员以对应类型重新解释同一块存储。借助堆上 `Array` 临时缓冲与
+    // `acquireArrayRawData` 取得稳定且对齐的原始指针再重解释；写入经由下标写回
+    // `this.storage`（与位域一致，真正改动本实例），从而 union 各视图彼此别名。
+    // `inout` 只能用作调用实参、不能用 `let` 绑定，且不应对值类型字段取内部指针。
+    for field in entity.get_children() {
+        if field.get_kind() != EntityKind::FieldDecl {
+            continue;
+        }
+        let field_name = match field.get_name() {
+            Some(n) => n,
+            None => continue,
+        };
+        let field_type = translate_type(field.get_type().unwrap());
+
+        if let Some(comment) = field.get_comment() {
+            writeln!(output, "    {}", comment)?;
+        }
+        writeln!(output, "    mut prop {}: {} {{", field_name, field_type)?;
+        writeln!(output, "        get() {{")?;
+        writeln!(
+            output,
+            "            let tmp = Array<{}>({}, repeat: 0)",
+            elem, count
+        )?;
+        writeln!(output, "            for (i in 0..{}) {{", count)?;
+        writeln!(output, "                tmp[i] = this.storage[i]")?;
+        writeln!(output, "            }}")?;
+        writeln!(output, "            unsafe {{")?;
+        writeln!(output, "                let h = acquireArrayRawData(tmp)")?;
+        writeln!(
+            output,
+            "                let v = CPointer<{}>(h.pointer).read()",
+            field_type
+        )?;
+        writeln!(output, "                releaseArrayRawData(h)")?;
+        writeln!(output, "                return v")?;
+        writeln!(output, "            }}")?;
+        writeln!(output, "        }}")?;
+        writeln!(output, "        set(value) {{")?;
+        writeln!(
+            output,
+            "            let tmp = Array<{}>({}, repeat: 0)",
+            elem, count
+        )?;
+        writeln!(output, "            unsafe {{")?;
+        writeln!(output, "                let h = acquireArrayRawData(tmp)")?;
+        writeln!(
+            output,
+            "                CPointer<{}>(h.pointer).write(value)",
+            field_type
+        )?;
+        writeln!(output, "                releaseArrayRawData(h)")?;
+        writeln!(output, "            }}")?;
+        writeln!(output, "            for (i in 0..{}) {{", count)?;
+        writeln!(output, "                this.storage[i] = tmp[i]")?;
+        writeln!(output, "            }}")?;
+        writeln!(output, "        }}")?;
+        writeln!(output, "    }}")?;
+    }
+
+    writeln!(output, "}}")?;
+    writeln!(output)?;
+
+    // 布局校验：生成类型的大小/对齐须与 clang 报告一致
+    emit_layout_check(&name, union_size, union_align, output)?;
+    Ok(())
+}
+
+fn process_function(entity: Entity, output: &mut dyn Write) -> std::io::Result<()> {
     let name = entity.get_name().unwrap();
     let return_type = translate_type(entity.get_result_type().unwrap());
 
@@ -245,53 +702,300 @@ fn process_function(entity: Entity, output: &mut File) -> std::io::Result<()> {
     Ok(())
 }
 
-struct typdef {
-    name: String,
-    typ: String,
-    comment: Option<String>,
+// 构造一个 C 形参声明 `<type> <name>`。函数指针参数的声明符必须把名字
+// 写在 `(*name)` 内，不能简单拼接类型显示名，否则生成的 C 无法编译。
+fn c_param_decl(ty: Type, var: &str) -> String {
+    if ty.get_kind() == clang::TypeKind::Pointer {
+        if let Some(pointee) = ty.get_pointee_type() {
+            if matches!(
+                pointee.get_kind(),
+                clang::TypeKind::FunctionPrototype | clang::TypeKind::FunctionNoPrototype
+            ) {
+                let ret = pointee.get_result_type().unwrap().get_display_name();
+                let params = pointee
+                    .get_argument_types()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|a| a.get_display_name())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                return format!("{} (*{})({})", ret, var, params);
+            }
+        }
+    }
+    format!("{} {}", ty.get_display_name(), var)
 }
 
-static mut TYPEDEFS: Vec<typdef> = Vec::new();
-
-fn process_typedef(entity: Entity) -> std::io::Result<()> {
+// inline 函数无导出符号，直接 foreign func 会链接失败；
+// 生成一个非 inline 的 C 包装转发到原函数，再绑定包装符号。
+fn emit_inline_wrapper(
+    entity: Entity,
+    wrappers: &mut String,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
     let name = entity.get_name().unwrap();
-    let underlying_type = entity.get_typedef_underlying_type().unwrap();
+    let wrapper_symbol = format!("{}__extern", name);
 
-    if name == translate_type(underlying_type) {
-        return Ok(());
+    let args: Vec<_> = entity.get_arguments().unwrap().into_iter().collect();
+
+    // C 侧：导出的非 inline 包装函数，转发到原 inline 函数
+    let c_ret = entity.get_result_type().unwrap().get_display_name();
+    let mut c_params: Vec<String> = Vec::new();
+    let mut call_args: Vec<String> = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        c_params.push(c_param_decl(arg.get_type().unwrap(), &format!("arg{}", i)));
+        call_args.push(format!("arg{}", i));
     }
+    wrappers.push_str(&format!(
+        "{} {}({}) {{\n",
+        c_ret,
+        wrapper_symbol,
+        c_params.join(", ")
+    ));
+    if c_ret == "void" {
+        wrappers.push_str(&format!("    {}({});\n", name, call_args.join(", ")));
+    } else {
+        wrappers.push_str(&format!("    return {}({});\n", name, call_args.join(", ")));
+    }
+    wrappers.push_str("}\n\n");
 
-    let t = typdef {
-        name: name,
-        typ: translate_type(underlying_type),
-        comment: entity.get_comment(),
-    };
-    unsafe {
-        TYPEDEFS.push(t);
+    // Cangjie 侧：绑定包装符号，并提供同名转发函数
+    let return_type = translate_type(entity.get_result_type().unwrap());
+    if let Some(comment) = entity.get_comment() {
+        writeln!(output, "{}", comment)?;
+    }
+
+    let mut decl_params: Vec<String> = Vec::new();
+    let mut fwd_args: Vec<String> = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        let mut arg_name = arg.get_name().unwrap_or(format!("arg{}", i));
+        if arg_name == "Unit" || arg_name == "type" {
+            arg_name = format!("{}_", arg_name);
+        }
+        let arg_type = translate_type(arg.get_type().unwrap());
+        decl_params.push(format!("{}: {}", arg_name, arg_type));
+        fwd_args.push(arg_name);
     }
 
+    writeln!(
+        output,
+        "foreign func {}({}): {}",
+        wrapper_symbol,
+        decl_params.join(", "),
+        return_type
+    )?;
+    writeln!(
+        output,
+        "func {}({}): {} {{",
+        name,
+        decl_params.join(", "),
+        return_type
+    )?;
+    writeln!(
+        output,
+        "    unsafe {{ return {}({}) }}",
+        wrapper_symbol,
+        fwd_args.join(", ")
+    )?;
+    writeln!(output, "}}")?;
+    writeln!(output)?;
     Ok(())
 }
 
-fn generate_typedefs(output: &mut File) -> std::io::Result<()> {
-    let names = STRUCT_NAMES.lock().unwrap();
-    for t in unsafe { TYPEDEFS.iter() } {
-        if !names.contains(&t.typ) {
-            continue;
-        }
+// 动态加载模式下收集到的一个导出函数
+struct DynFunction {
+    name: String,
+    // 复用 translate_type 的 CFunc<...> 映射
+    cfunc_type: String,
+    comment: Option<String>,
+}
+
+fn collect_dyn_function(entity: Entity) -> DynFunction {
+    DynFunction {
+        name: entity.get_name().unwrap(),
+        cfunc_type: translate_type(entity.get_type().unwrap()),
+        comment: entity.get_comment(),
+    }
+}
+
+// 生成 `struct Library`：每个导出函数一个函数指针字段，外加运行时加载的初始化器
+fn generate_library(
+    funcs: &[DynFunction],
+    allow_missing: bool,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    // dlopen/dlsym 运行时依赖
+    writeln!(output, "foreign func dlopen(filename: CString, flags: Int32): CPointer<Unit>")?;
+    writeln!(output, "foreign func dlsym(handle: CPointer<Unit>, symbol: CString): CPointer<Unit>")?;
+    writeln!(output)?;
 
-        if let Some(comment) = &t.comment {
-            writeln!(output, "{}", comment)?;
+    writeln!(output, "@C")?;
+    writeln!(output, "struct Library {{")?;
+    writeln!(output, "    var handle: CPointer<Unit> = CPointer()")?;
+    for f in funcs {
+        if let Some(comment) = &f.comment {
+            writeln!(output, "    {}", comment)?;
         }
-        writeln!(output, "type {} = {}", t.name, t.typ)?;
+        writeln!(
+            output,
+            "    var {}: {} = {}",
+            f.name,
+            f.cfunc_type,
+            get_default(f.cfunc_type.clone())
+        )?;
     }
+    writeln!(output)?;
 
+    // 初始化器：dlopen 指定库，再逐个 dlsym 到对应字段
+    writeln!(output, "    init(path: String) {{")?;
+    writeln!(output, "        unsafe {{")?;
+    writeln!(
+        output,
+        "            this.handle = dlopen(path.toCString(), 2)"
+    )?;
+    for f in funcs {
+        writeln!(
+            output,
+            "            let sym_{} = dlsym(this.handle, \"{}\".toCString())",
+            f.name, f.name
+        )?;
+        if !allow_missing {
+            // 缺失符号视为致命错误
+            writeln!(output, "            if (sym_{}.isNull()) {{", f.name)?;
+            writeln!(
+                output,
+                "                throw Exception(\"未能解析符号: {}\")",
+                f.name
+            )?;
+            writeln!(output, "            }}")?;
+        }
+        // 允许缺失时，空符号使字段保持默认空值
+        writeln!(output, "            if (!sym_{}.isNull()) {{", f.name)?;
+        writeln!(
+            output,
+            "                this.{} = CPointer.toFunc<{}>(sym_{})",
+            f.name, f.cfunc_type, f.name
+        )?;
+        writeln!(output, "            }}")?;
+    }
+    writeln!(output, "        }}")?;
+    writeln!(output, "    }}")?;
+    writeln!(output, "}}")?;
+    writeln!(output)?;
     Ok(())
 }
 
+// 已渲染好的一个类型声明及其对其它类型的依赖，用于拓扑排序。
+struct TypeDecl {
+    name: String,
+    deps: Vec<String>,
+    text: String,
+}
+
+// 渲染一个 typedef：与旧行为一致，仅当底层类型是已知 struct/union 时才输出；
+// 返回 (依赖名, 文本)。
+fn render_typedef(entity: Entity) -> Option<(String, String)> {
+    let name = entity.get_name().unwrap();
+    let underlying = entity.get_typedef_underlying_type().unwrap();
+    let typ = translate_type(underlying);
+    if name == typ {
+        return None;
+    }
+    let is_record = {
+        let names = STRUCT_NAMES.lock().unwrap();
+        let unions = UNION_NAMES.lock().unwrap();
+        names.contains(&typ) || unions.contains(&typ)
+    };
+    if !is_record {
+        return None;
+    }
+    let mut text = String::new();
+    if let Some(comment) = entity.get_comment() {
+        text.push_str(&comment);
+        text.push('\n');
+    }
+    text.push_str(&format!("type {} = {}\n", name, typ));
+    Some((typ, text))
+}
+
+// 为“被屏蔽但仍被传递依赖引用”的类型渲染不透明占位，保证引用处可编译。
+// enum 退化为 Int32 别名；struct/union 退化为按 sizeof 对齐的字节块。
+fn render_opaque(entity: Entity) -> std::io::Result<Option<String>> {
+    let name = entity.get_name().unwrap_or_default();
+    match entity.get_kind() {
+        EntityKind::EnumDecl => Ok(Some(format!("type {} = Int32\n\n", name))),
+        EntityKind::StructDecl | EntityKind::UnionDecl => {
+            let ty = entity.get_type().unwrap();
+            let size = ty.get_sizeof().unwrap_or(0);
+            let align = ty.get_alignof().unwrap_or(0);
+            let (elem, count) = aligned_layout(size, align);
+            render(|w| {
+                writeln!(w, "// {}: 被屏蔽，按不透明字节块输出", name)?;
+                writeln!(w, "@C")?;
+                writeln!(w, "struct {} {{", name)?;
+                writeln!(
+                    w,
+                    "    var _opaque: VArray<{}, ${}> = VArray<{}, ${}>(repeat: 0)",
+                    elem, count, elem, count
+                )?;
+                writeln!(w, "}}")?;
+                writeln!(w)
+            })
+            .map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+// 将一个 process_* 渲染到字符串缓冲区
+fn render<F>(f: F) -> std::io::Result<String>
+where
+    F: FnOnce(&mut dyn Write) -> std::io::Result<()>,
+{
+    let mut buf: Vec<u8> = Vec::new();
+    f(&mut buf)?;
+    Ok(String::from_utf8(buf).expect("生成的文本应为合法 UTF-8"))
+}
+
+// 深度优先拓扑排序：依赖先于使用者，缺失名/成环的回边被忽略。
+fn topo_order(decls: &[TypeDecl]) -> Vec<usize> {
+    let index: HashMap<&str, usize> = decls
+        .iter()
+        .enumerate()
+        .map(|(i, d)| (d.name.as_str(), i))
+        .collect();
+    let mut state = vec![0u8; decls.len()]; // 0 未访问 / 1 访问中 / 2 完成
+    let mut order: Vec<usize> = Vec::new();
+    for i in 0..decls.len() {
+        topo_visit(i, decls, &index, &mut state, &mut order);
+    }
+    order
+}
+
+fn topo_visit(
+    i: usize,
+    decls: &[TypeDecl],
+    index: &HashMap<&str, usize>,
+    state: &mut [u8],
+    order: &mut Vec<usize>,
+) {
+    if state[i] != 0 {
+        return;
+    }
+    state[i] = 1;
+    for dep in &decls[i].deps {
+        if let Some(&j) = index.get(dep.as_str()) {
+            topo_visit(j, decls, index, state, order);
+        }
+    }
+    state[i] = 2;
+    order.push(i);
+}
+
 pub fn generate_bindings(
     header_path: &str,
     output_path: &str,
+    options: &Options,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let clang = Clang::new()?;
     let index = clang::Index::new(&clang, false, true);
@@ -312,43 +1016,345 @@ pub fn generate_bindings(
     writeln!(output)?;
     writeln!(output, "package clang_cj")?;
 
-    for entity in tu.get_entity().get_children() {
-        if entity.is_in_system_header() {
-            continue;
+    // 收集候选的非系统头实体，并按名字建立类型声明索引，
+    // 以便先计算依赖闭包、再决定导出集合。
+    let candidates: Vec<Entity> = tu
+        .get_entity()
+        .get_children()
+        .into_iter()
+        .filter(|e| !e.is_in_system_header())
+        .collect();
+
+    let mut type_decls: HashMap<String, Entity> = HashMap::new();
+    for entity in &candidates {
+        match entity.get_kind() {
+            EntityKind::StructDecl
+            | EntityKind::UnionDecl
+            | EntityKind::EnumDecl
+            | EntityKind::TypedefDecl => {
+                if let Some(name) = entity.get_name() {
+                    // 同名的前向声明与定义都会出现；优先保留完整定义
+                    match type_decls.get(&name) {
+                        Some(existing) if existing.is_definition() => {}
+                        _ => {
+                            type_decls.insert(name, *entity);
+                        }
+                    }
+                }
+            }
+            _ => (),
         }
+    }
+
+    let filtering = options.filtering_active();
+
+    // 根据允许/屏蔽列表确定根实体，并以工作表计算传递性类型依赖闭包。
+    let mut wanted_types: HashSet<String> = HashSet::new();
+    let mut worklist: Vec<String> = Vec::new();
 
+    for entity in &candidates {
+        let name = match entity.get_name() {
+            Some(n) => n,
+            None => continue,
+        };
         match entity.get_kind() {
-            EntityKind::EnumDecl => process_enum(entity, &mut output)?,
-            EntityKind::FunctionDecl => process_function(entity, &mut output)?,
-            EntityKind::TypedefDecl => process_typedef(entity)?,
+            EntityKind::StructDecl
+            | EntityKind::UnionDecl
+            | EntityKind::EnumDecl
+            | EntityKind::TypedefDecl => {
+                if is_root(
+                    &name,
+                    &options.allowlist_type,
+                    &options.blocklist_type,
+                    filtering,
+                ) && wanted_types.insert(name.clone())
+                {
+                    worklist.push(name);
+                }
+            }
+            EntityKind::FunctionDecl => {
+                if is_root(
+                    &name,
+                    &options.allowlist_function,
+                    &options.blocklist_function,
+                    filtering,
+                ) {
+                    // 函数签名引用的类型一并纳入闭包
+                    if let Some(ret) = entity.get_result_type() {
+                        collect_deps(ret, &mut wanted_types);
+                    }
+                    if let Some(args) = entity.get_arguments() {
+                        for arg in args {
+                            if let Some(ty) = arg.get_type() {
+                                collect_deps(ty, &mut wanted_types);
+                            }
+                        }
+                    }
+                }
+            }
+            EntityKind::VarDecl => {
+                // 基线不导出任何全局变量；仅在显式指定 --allowlist-var 时才处理
+                if !options.allowlist_var.is_empty()
+                    && is_root(
+                        &name,
+                        &options.allowlist_var,
+                        &options.blocklist_var,
+                        filtering,
+                    )
+                {
+                    if let Some(ty) = entity.get_type() {
+                        collect_deps(ty, &mut wanted_types);
+                    }
+                }
+            }
             _ => (),
         }
     }
 
-    for entity in tu.get_entity().get_children() {
-        if entity.is_in_system_header() {
+    // 函数/变量依赖带来的新类型也要进入工作表
+    for name in wanted_types.clone() {
+        worklist.push(name);
+    }
+
+    while let Some(name) = worklist.pop() {
+        if let Some(decl) = type_decls.get(&name) {
+            let mut deps: HashSet<String> = HashSet::new();
+            decl_deps(decl, &mut deps);
+            for dep in deps {
+                if wanted_types.insert(dep.clone()) {
+                    worklist.push(dep);
+                }
+            }
+        }
+    }
+
+    // 某类型是否应当导出：在闭包内且未被屏蔽
+    let emit_type = |name: &str| -> bool {
+        wanted_types.contains(name) && !matches_any(name, &options.blocklist_type)
+    };
+
+    // 先把每个要导出的声明渲染到缓冲区，连同其类型依赖，
+    // 之后再拓扑排序，保证类型先于使用它的声明出现。
+    let mut type_decls_out: Vec<TypeDecl> = Vec::new();
+    // 需要接入聚合布局校验的类型（每个 struct/union 都会生成 layout_check_*）
+    let mut layout_checks: Vec<String> = Vec::new();
+
+    // enum / struct / union 先渲染，以便填充名称集合供 typedef 判断
+    for entity in candidates.iter().copied() {
+        let name = entity.get_name().unwrap_or_default();
+        if !wanted_types.contains(&name) {
+            continue;
+        }
+        // 前向声明（`struct Foo;`）会与定义同名重复出现且 sizeof 为 0；
+        // 只渲染完整定义，避免重复的 struct/union 与 layout_check_*。
+        if matches!(
+            entity.get_kind(),
+            EntityKind::StructDecl | EntityKind::UnionDecl
+        ) && !entity.is_definition()
+        {
+            continue;
+        }
+        // 被屏蔽但被引用：输出不透明占位（并告警），否则引用处无定义无法编译
+        if matches_any(&name, &options.blocklist_type) {
+            if let Some(text) = render_opaque(entity)? {
+                eprintln!(
+                    "Warning: 类型 `{}` 被屏蔽但被其它导出实体引用，以不透明占位输出",
+                    name
+                );
+                type_decls_out.push(TypeDecl {
+                    name,
+                    deps: Vec::new(),
+                    text,
+                });
+            }
             continue;
         }
+        let (text, deps) = match entity.get_kind() {
+            EntityKind::EnumDecl => (render(|w| process_enum(entity, w))?, HashSet::new()),
+            EntityKind::StructDecl => {
+                let mut deps = HashSet::new();
+                decl_deps(&entity, &mut deps);
+                layout_checks.push(name.clone());
+                (render(|w| process_struct(entity, w))?, deps)
+            }
+            EntityKind::UnionDecl => {
+                let mut deps = HashSet::new();
+                decl_deps(&entity, &mut deps);
+                layout_checks.push(name.clone());
+                (render(|w| process_union(entity, w))?, deps)
+            }
+            _ => continue,
+        };
+        // 依赖来自 HashSet，其迭代顺序每次运行随机；排序以保证输出稳定
+        let mut deps: Vec<String> = deps.into_iter().collect();
+        deps.sort();
+        type_decls_out.push(TypeDecl { name, deps, text });
+    }
 
-        match entity.get_kind() {
-            EntityKind::StructDecl => process_struct(entity, &mut output)?,
-            _ => (),
+    // typedef 依赖其底层 struct/union 名称
+    for entity in candidates.iter().copied() {
+        if entity.get_kind() != EntityKind::TypedefDecl {
+            continue;
+        }
+        let name = entity.get_name().unwrap_or_default();
+        if !emit_type(&name) {
+            continue;
+        }
+        if let Some((dep, text)) = render_typedef(entity) {
+            type_decls_out.push(TypeDecl {
+                name,
+                deps: vec![dep],
+                text,
+            });
         }
     }
 
-    generate_typedefs(&mut output)?;
+    // 拓扑排序后依序写出类型
+    for idx in topo_order(&type_decls_out) {
+        write!(output, "{}", type_decls_out[idx].text)?;
+    }
+
+    // 聚合布局校验，使各 layout_check_* 真正可达
+    emit_layout_check_all(&layout_checks, &mut output)?;
+
+    // 变量分组写出
+    for entity in candidates.iter().copied() {
+        if entity.get_kind() != EntityKind::VarDecl {
+            continue;
+        }
+        let name = entity.get_name().unwrap_or_default();
+        // 基线不导出任何全局变量；仅在显式指定 --allowlist-var 时才导出
+        if !options.allowlist_var.is_empty()
+            && is_root(
+                &name,
+                &options.allowlist_var,
+                &options.blocklist_var,
+                filtering,
+            )
+        {
+            process_var(entity, &mut output)?;
+        }
+    }
+
+    // 函数统一分组写出：动态加载模式下合并为单个 Library
+    let mut dyn_functions: Vec<DynFunction> = Vec::new();
+    // static inline 函数的 C 包装源
+    let mut wrappers = String::new();
+    for entity in candidates.iter().copied() {
+        if entity.get_kind() != EntityKind::FunctionDecl {
+            continue;
+        }
+        let name = entity.get_name().unwrap_or_default();
+        if !is_root(
+            &name,
+            &options.allowlist_function,
+            &options.blocklist_function,
+            filtering,
+        ) {
+            continue;
+        }
+        // 任何在头文件中内联定义的函数（有函数体且带 inline 说明符）在本 TU 都
+        // 不导出符号——无论是否 `static`；C99 的 `inline int f(){...}` 亦然。
+        // 这些都需要包装，否则 foreign func 会链接失败。
+        let is_inline_definition = entity.is_definition() && entity.is_inline_function();
+        if options.wrap_static_fns && is_inline_definition {
+            emit_inline_wrapper(entity, &mut wrappers, &mut output)?;
+        } else if options.dynamic_loading {
+            dyn_functions.push(collect_dyn_function(entity));
+        } else {
+            process_function(entity, &mut output)?;
+        }
+    }
+
+    if options.dynamic_loading {
+        generate_library(&dyn_functions, options.allow_missing_symbols, &mut output)?;
+    }
+
+    // 有 inline 包装时，生成可与绑定一同编译的 C 源文件
+    if !wrappers.is_empty() {
+        let wrappers_path = format!("{}.wrappers.c", output_path);
+        let mut wf = File::create(Path::new(&wrappers_path))?;
+        writeln!(wf, "// This file is automatically generated. DO NOT EDIT.")?;
+        // 包装源写在输出目录而非 CWD，相对头路径会失效；规范化为绝对路径
+        let include_path = std::fs::canonicalize(header_path)
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|_| header_path.to_string());
+        writeln!(wf, "#include \"{}\"", include_path)?;
+        writeln!(wf)?;
+        write!(wf, "{}", wrappers)?;
+    }
 
     Ok(())
 }
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <input.h> <output.cj>", args[0]);
+
+    let mut options = Options::default();
+    let mut positional: Vec<String> = Vec::new();
+
+    // 将正则参数编译进对应列表
+    let push_regex = |list: &mut Vec<Regex>, pattern: Option<&String>| {
+        let pattern = pattern.unwrap_or_else(|| {
+            eprintln!("Error: 缺少正则参数");
+            std::process::exit(1);
+        });
+        match Regex::new(pattern) {
+            Ok(re) => list.push(re),
+            Err(e) => {
+                eprintln!("Error: 非法正则 `{}`: {}", pattern, e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--dynamic-loading" => options.dynamic_loading = true,
+            "--allow-missing-symbols" => options.allow_missing_symbols = true,
+            "--wrap-static-fns" => options.wrap_static_fns = true,
+            "--allowlist-function" => {
+                push_regex(&mut options.allowlist_function, args.get(i + 1));
+                i += 1;
+            }
+            "--allowlist-type" => {
+                push_regex(&mut options.allowlist_type, args.get(i + 1));
+                i += 1;
+            }
+            "--allowlist-var" => {
+                push_regex(&mut options.allowlist_var, args.get(i + 1));
+                i += 1;
+            }
+            "--blocklist-function" => {
+                push_regex(&mut options.blocklist_function, args.get(i + 1));
+                i += 1;
+            }
+            "--blocklist-type" => {
+                push_regex(&mut options.blocklist_type, args.get(i + 1));
+                i += 1;
+            }
+            "--blocklist-var" => {
+                push_regex(&mut options.blocklist_var, args.get(i + 1));
+                i += 1;
+            }
+            other => positional.push(other.to_string()),
+        }
+        i += 1;
+    }
+
+    if positional.len() != 2 {
+        eprintln!(
+            "Usage: {} [--dynamic-loading] [--allow-missing-symbols] [--wrap-static-fns] \
+             [--allowlist-function RE] [--allowlist-type RE] [--allowlist-var RE] \
+             [--blocklist-function RE] [--blocklist-type RE] [--blocklist-var RE] \
+             <input.h> <output.cj>",
+            args[0]
+        );
         std::process::exit(1);
     }
 
-    match generate_bindings(&args[1], &args[2]) {
+    match generate_bindings(&positional[0], &positional[1], &options) {
         Ok(_) => println!("Successfully generated bindings"),
         Err(e) => {
             eprintln!("Error generating bindings: {}", e);